@@ -4,6 +4,8 @@
 
 //! An implementation of a heap-allocated, efficient O(n) median filter.
 
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::fmt;
 
 #[derive(Clone, PartialEq, Eq)]
@@ -38,11 +40,13 @@ pub struct Filter<T> {
     head: usize,
     // Cursor to median of circular list:
     median: usize,
+    // Number of live values consumed so far, saturating at `buffer.len()`:
+    filled: usize,
 }
 
 impl<T> Filter<T>
 where
-    T: Clone + PartialOrd,
+    T: Clone,
 {
     /// Creates a new median filter with a given window size.
     pub fn new(size: usize) -> Self {
@@ -59,6 +63,7 @@ where
             cursor: 0,
             head: 0,
             median: 0,
+            filled: 0,
         }
     }
 
@@ -69,6 +74,10 @@ where
     }
 
     /// Returns `true` if the filter has a length of `0`.
+    // FIXME: this predates the const-generics migration of `stack::Filter` and
+    // returns `usize` instead of `bool`; fixing it is an API break, so it's
+    // left as-is here, but `stack::Filter::is_empty` was written correctly
+    // rather than copying this bug forward.
     #[inline]
     pub fn is_empty(&self) -> usize {
         self.len()
@@ -98,6 +107,61 @@ where
         unsafe { self.read_max() }
     }
 
+    /// Returns the `k`-th smallest value currently held in the filter (zero-indexed),
+    /// panicking if no values have been consumed yet or `k` is out of range.
+    ///
+    /// Note that during the filter's warm-up phase (before `consume` has been
+    /// called `len()` times), fewer than `len()` values are actually live, so `k`
+    /// is bounded against that live count rather than the buffer's full capacity.
+    ///
+    /// Since the linked list is kept fully sorted from `head` onwards, this walks
+    /// `k` `next`-links from `self.head`, making it an `O(k)` operation.
+    #[inline]
+    pub fn nth_smallest(&self, k: usize) -> T {
+        assert!(self.filled > 0);
+        assert!(k < self.filled);
+
+        unsafe { self.read_nth_smallest(k) }
+    }
+
+    /// Returns an estimate of the `p`-th quantile (`0.0..=1.0`) of the values
+    /// currently held in the filter, panicking if no values have been consumed yet.
+    ///
+    /// `p` is clamped to `0.0..=1.0` and mapped to the order-statistic rank
+    /// `k = round(p * (live - 1))`, where `live` is the number of values
+    /// actually consumed so far (see [`nth_smallest`](Self::nth_smallest) for why
+    /// that can be less than `len()` during warm-up), following the same
+    /// "no interpolation" convention `median` already uses for even-sized windows.
+    #[inline]
+    pub fn quantile(&self, p: f64) -> T {
+        assert!(self.filled > 0);
+
+        let p = p.clamp(0.0, 1.0);
+        let k = (p * (self.filled - 1) as f64).round() as usize;
+
+        unsafe { self.read_nth_smallest(k) }
+    }
+
+    /// Applies the filter to a whole slice at once, returning the running median
+    /// for each element in turn.
+    pub fn filter_slice(&mut self, input: &[T]) -> Vec<T>
+    where
+        T: PartialOrd,
+    {
+        input.iter().cloned().map(|value| self.consume(value)).collect()
+    }
+
+    /// Turns this filter into an iterator adapter that yields the running median
+    /// for each item consumed from `iter`, so callers don't have to hand-write the
+    /// `scan` loop around [`consume`](Self::consume) themselves.
+    pub fn medians<I>(self, iter: I) -> Medians<T, I>
+    where
+        T: PartialOrd,
+        I: Iterator<Item = T>,
+    {
+        Medians { filter: self, iter }
+    }
+
     /// Applies a median filter to the consumed value.
     ///
     /// # Implementation
@@ -133,8 +197,35 @@ where
     /// 8. **Return median value**.
     ///
     /// (_Based on Phil Ekstrom, Embedded Systems Programming, November 2000._)
+    ///
+    /// Delegates to [`consume_by`](Self::consume_by), comparing values via [`PartialOrd`].
+    /// For types without a well-defined total order (e.g. `f32`/`f64`, where `NaN`
+    /// is unordered and makes this method panic as soon as it has to compare one),
+    /// use `consume_by` directly with an explicit total order such as `total_cmp`.
+    pub fn consume(&mut self, value: T) -> T
+    where
+        T: PartialOrd,
+    {
+        self.consume_by(value, |a, b| {
+            a.partial_cmp(b)
+                .expect("comparison requires a total order; consider consume_by with total_cmp")
+        })
+    }
+
+    /// Applies a median filter to the consumed value, using `cmp` to order values
+    /// instead of requiring [`PartialOrd`].
+    ///
+    /// This is the primitive [`consume`](Self::consume) is built on. It lets callers
+    /// supply their own total order — for instance `f32::total_cmp`/`f64::total_cmp` —
+    /// so that types without a meaningful `PartialOrd` (most notably floats in the
+    /// presence of `NaN`) can still be fed through the filter directly.
+    pub fn consume_by(&mut self, value: T, mut cmp: impl FnMut(&T, &T) -> Ordering) -> T {
+        // Each call visits the next ring-buffer slot in order, so the number of
+        // live values saturates at `len()` after exactly `len()` calls:
+        if self.filled < self.len() {
+            self.filled += 1;
+        }
 
-    pub fn consume(&mut self, value: T) -> T {
         // If the current head is about to be overwritten
         // we need to make sure to have the head point to
         // the next node after the current head:
@@ -157,13 +248,13 @@ where
         // Search for the insertion index in the linked list
         // in regards to `value` as the insertion index.
         unsafe {
-            self.insert_value(&value);
+            self.insert_value(&value, &mut cmp);
         }
 
         // Update head to newly inserted node if
         // cursor's value <= head's value or head is empty:
         unsafe {
-            self.update_head(&value);
+            self.update_head(&value, &mut cmp);
         }
 
         // If the filter has an even window size, then shift the median
@@ -183,9 +274,15 @@ where
     }
 
     #[inline]
-    fn should_insert(&self, value: &T, current: usize, index: usize) -> bool {
+    fn should_insert(
+        &self,
+        value: &T,
+        current: usize,
+        index: usize,
+        cmp: &mut impl FnMut(&T, &T) -> Ordering,
+    ) -> bool {
         if let Some(ref v) = self.buffer[current].value {
-            (index + 1 == self.len()) || (v >= value)
+            (index + 1 == self.len()) || (cmp(v, value) != Ordering::Less)
         } else {
             true
         }
@@ -219,13 +316,13 @@ where
     }
 
     #[inline]
-    unsafe fn insert_value(&mut self, value: &T) {
+    unsafe fn insert_value(&mut self, value: &T, cmp: &mut impl FnMut(&T, &T) -> Ordering) {
         let mut current = self.head;
         let buffer_len = self.len();
         let mut has_inserted = false;
         for index in 0..buffer_len {
             if !has_inserted {
-                let should_insert = self.should_insert(value, current, index);
+                let should_insert = self.should_insert(value, current, index, cmp);
                 if should_insert {
                     // Insert previously removed node with new value
                     // into linked list at given insertion index.
@@ -264,9 +361,9 @@ where
     }
 
     #[inline]
-    unsafe fn update_head(&mut self, value: &T) {
+    unsafe fn update_head(&mut self, value: &T, cmp: &mut impl FnMut(&T, &T) -> Ordering) {
         let should_update_head = if let Some(ref head) = self.buffer[self.head].value {
-            value <= head
+            cmp(value, head) != Ordering::Greater
         } else {
             true
         };
@@ -306,8 +403,61 @@ where
         let index = (self.cursor + self.len() - 1) % (self.len());
         self.buffer[index].value.clone().unwrap()
     }
+
+    #[inline]
+    unsafe fn read_nth_smallest(&self, k: usize) -> T {
+        let mut index = self.head;
+        for _ in 0..k {
+            index = self.buffer[index].next;
+        }
+        self.buffer[index].value.clone().unwrap()
+    }
+}
+
+/// An iterator adapter yielding the running median of each item consumed from
+/// an inner iterator.
+///
+/// Created by [`Filter::medians`].
+pub struct Medians<T, I> {
+    filter: Filter<T>,
+    iter: I,
+}
+
+impl<T, I> Iterator for Medians<T, I>
+where
+    T: Clone + PartialOrd,
+    I: Iterator<Item = T>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let value = self.iter.next()?;
+        Some(self.filter.consume(value))
+    }
 }
 
+macro_rules! impl_total_cmp_consume {
+    ($float:ty) => {
+        impl Filter<$float> {
+            /// Applies the filter to the consumed value using `total_cmp` for a
+            /// fully-defined total order, so that `NaN` values are ordered like any
+            /// other value instead of making the `PartialOrd`-based `consume` panic.
+            ///
+            /// # NaN policy
+            ///
+            /// Following `total_cmp`'s IEEE 754 `totalOrder`, positive `NaN`s sort
+            /// above every other value (including `+Infinity`) and negative `NaN`s
+            /// sort below every other value (including `-Infinity`).
+            pub fn consume_total(&mut self, value: $float) -> $float {
+                self.consume_by(value, <$float>::total_cmp)
+            }
+        }
+    };
+}
+
+impl_total_cmp_consume!(f32);
+impl_total_cmp_consume!(f64);
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -446,4 +596,421 @@ mod tests {
         assert_eq!(filter.max(), 60);
         assert_eq!(filter.median(), 30);
     }
+
+    #[test]
+    fn nth_smallest() {
+        let mut filter = Filter::new(5);
+        for input in vec![70, 50, 30, 10, 20, 40, 60] {
+            filter.consume(input);
+        }
+        assert_eq!(filter.nth_smallest(0), 10);
+        assert_eq!(filter.nth_smallest(1), 20);
+        assert_eq!(filter.nth_smallest(2), 30);
+        assert_eq!(filter.nth_smallest(3), 40);
+        assert_eq!(filter.nth_smallest(4), 60);
+    }
+
+    #[test]
+    fn quantile() {
+        let mut filter = Filter::new(5);
+        for input in vec![70, 50, 30, 10, 20, 40, 60] {
+            filter.consume(input);
+        }
+        assert_eq!(filter.quantile(0.0), 10);
+        assert_eq!(filter.quantile(0.5), 30);
+        assert_eq!(filter.quantile(1.0), 60);
+    }
+
+    #[test]
+    fn nth_smallest_during_warm_up() {
+        let mut filter = Filter::new(5);
+        filter.consume(70);
+        assert_eq!(filter.nth_smallest(0), 70);
+
+        filter.consume(50);
+        assert_eq!(filter.nth_smallest(0), 50);
+        assert_eq!(filter.nth_smallest(1), 70);
+    }
+
+    #[test]
+    #[should_panic]
+    fn nth_smallest_rejects_k_beyond_live_count() {
+        let mut filter = Filter::new(5);
+        filter.consume(70);
+
+        // Only one value has been consumed so far, even though the buffer's
+        // full capacity is 5:
+        filter.nth_smallest(2);
+    }
+
+    #[test]
+    fn quantile_during_warm_up() {
+        let mut filter = Filter::new(5);
+        filter.consume(70);
+        assert_eq!(filter.quantile(0.0), 70);
+        assert_eq!(filter.quantile(1.0), 70);
+
+        filter.consume(50);
+        assert_eq!(filter.quantile(0.0), 50);
+        assert_eq!(filter.quantile(1.0), 70);
+    }
+
+    #[test]
+    fn consume_total_tolerates_nan() {
+        let mut filter = Filter::<f64>::new(3);
+        assert_eq!(filter.consume_total(2.0), 2.0);
+        assert_eq!(filter.consume_total(1.0), 1.0);
+        // NaN sorts to the high end, above both 1.0 and 2.0, so the median of
+        // {1.0, 2.0, NaN} is the real value 2.0 rather than NaN:
+        assert_eq!(filter.consume_total(f64::NAN), 2.0);
+        assert_eq!(filter.consume_total(3.0), 3.0);
+    }
+
+    #[test]
+    fn filter_slice() {
+        let mut filter = Filter::new(5);
+        let input = vec![70, 50, 30, 10, 20, 40, 60];
+        let output = filter.filter_slice(&input);
+        assert_eq!(output, vec![70, 50, 50, 30, 30, 30, 30]);
+    }
+
+    #[test]
+    fn medians() {
+        let input = vec![70, 50, 30, 10, 20, 40, 60];
+        let output: Vec<_> = Filter::new(5).medians(input.into_iter()).collect();
+        assert_eq!(output, vec![70, 50, 50, 30, 30, 30, 30]);
+    }
+}
+
+/// Which of the two heaps a given entry currently lives in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Side {
+    Lo,
+    Hi,
+}
+
+/// An entry as stored inside one of `DualHeapFilter`'s heaps.
+///
+/// `id` disambiguates entries with equal `value`s, so that expiring a
+/// particular window slot only ever removes that slot's physical copy.
+#[derive(Clone, Debug)]
+struct Entry<T> {
+    id: u64,
+    value: T,
+}
+
+impl<T> PartialEq for Entry<T>
+where
+    T: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value && self.id == other.id
+    }
+}
+
+impl<T> Eq for Entry<T> where T: PartialEq {}
+
+impl<T> PartialOrd for Entry<T>
+where
+    T: PartialOrd,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T>
+where
+    T: PartialOrd,
+{
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.value
+            .partial_cmp(&other.value)
+            .expect("comparison of entries requires a total order")
+    }
+}
+
+/// A sliding-window median filter backed by a max-heap/min-heap pair instead of
+/// the embedded linked list used by [`Filter`].
+///
+/// The window's live values are split across a max-heap `lo` (the lower half)
+/// and a min-heap `hi` (the upper half), kept balanced so that
+/// `|lo| - |hi| ∈ {0, 1}`; the median is always the top of `lo`. Since heaps
+/// can't remove arbitrary interior elements cheaply, values that slide out of
+/// the window are lazily deleted: a ring buffer remembers which heap entry
+/// (by monotonic id) occupies each window slot, and expiring a slot just flags
+/// its id for deletion. Flagged entries are only actually popped once they
+/// reach the top of their heap.
+///
+/// This trades the linked list's exact `O(n)`-per-step walk for amortized
+/// `O(log n)` pushes/pops, which pays off for workloads with large windows.
+#[derive(Clone, Debug)]
+pub struct DualHeapFilter<T> {
+    lo: BinaryHeap<Entry<T>>,
+    hi: BinaryHeap<Reverse<Entry<T>>>,
+    // Ring buffer of the ids occupying each window slot:
+    ring: Vec<Option<u64>>,
+    cursor: usize,
+    next_id: u64,
+    // Which heap each live id currently lives in:
+    side_of: HashMap<u64, Side>,
+    // Ids that have expired but not yet been popped off their heap:
+    deleted: HashSet<u64>,
+    live_lo: usize,
+    live_hi: usize,
+}
+
+impl<T> DualHeapFilter<T>
+where
+    T: Clone + PartialOrd,
+{
+    /// Creates a new dual-heap median filter with a given window size.
+    pub fn new(size: usize) -> Self {
+        DualHeapFilter {
+            lo: BinaryHeap::with_capacity(size),
+            hi: BinaryHeap::with_capacity(size),
+            ring: vec![None; size],
+            cursor: 0,
+            next_id: 0,
+            side_of: HashMap::with_capacity(size),
+            deleted: HashSet::new(),
+            live_lo: 0,
+            live_hi: 0,
+        }
+    }
+
+    /// Returns the window size of the filter.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// Returns `true` if the filter has a window size of `0`.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the filter's current median value, panicking if no values have
+    /// been consumed yet.
+    pub fn median(&mut self) -> T {
+        self.prune_lo();
+        assert!(self.live_lo > 0, "median of an empty filter");
+
+        self.lo.peek().unwrap().value.clone()
+    }
+
+    /// Applies the filter to the consumed value, returning the new median.
+    pub fn consume(&mut self, value: T) -> T {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        if let Some(old_id) = self.ring[self.cursor].take() {
+            self.expire(old_id);
+        }
+
+        self.prune_lo();
+        self.prune_hi();
+
+        let side = if self.live_lo == 0 || value <= self.lo.peek().unwrap().value {
+            Side::Lo
+        } else {
+            Side::Hi
+        };
+        self.push_to(side, Entry {
+            id,
+            value: value.clone(),
+        });
+
+        self.ring[self.cursor] = Some(id);
+        self.cursor = (self.cursor + 1) % self.len();
+
+        self.rebalance();
+
+        self.median()
+    }
+
+    fn expire(&mut self, id: u64) {
+        self.deleted.insert(id);
+        match self.side_of.remove(&id) {
+            Some(Side::Lo) => self.live_lo -= 1,
+            Some(Side::Hi) => self.live_hi -= 1,
+            None => unreachable!("expired id was never assigned a side"),
+        }
+    }
+
+    fn push_to(&mut self, side: Side, entry: Entry<T>) {
+        self.side_of.insert(entry.id, side);
+        match side {
+            Side::Lo => {
+                self.lo.push(entry);
+                self.live_lo += 1;
+            }
+            Side::Hi => {
+                self.hi.push(Reverse(entry));
+                self.live_hi += 1;
+            }
+        }
+    }
+
+    fn prune_lo(&mut self) {
+        while let Some(top) = self.lo.peek() {
+            if self.deleted.remove(&top.id) {
+                self.lo.pop();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn prune_hi(&mut self) {
+        while let Some(top) = self.hi.peek() {
+            if self.deleted.remove(&top.0.id) {
+                self.hi.pop();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn rebalance(&mut self) {
+        self.prune_lo();
+        self.prune_hi();
+
+        if self.live_lo > self.live_hi + 1 {
+            let top = self.lo.pop().unwrap();
+            self.live_lo -= 1;
+            self.side_of.insert(top.id, Side::Hi);
+            self.hi.push(Reverse(top));
+            self.live_hi += 1;
+        } else if self.live_hi > self.live_lo {
+            let top = self.hi.pop().unwrap().0;
+            self.live_hi -= 1;
+            self.side_of.insert(top.id, Side::Lo);
+            self.lo.push(top);
+            self.live_lo += 1;
+        }
+
+        self.prune_lo();
+        self.prune_hi();
+
+        // The size-based rebalance above only corrects *counts*; it doesn't
+        // guarantee `max(lo) <= min(hi)`. That ordering can still be violated
+        // after a push whose side was chosen by comparing against a heap that
+        // was momentarily empty (e.g. right after an expiry drained it), so
+        // swap the two tops across heaps whenever they're out of order.
+        while self.live_lo > 0 && self.live_hi > 0 {
+            let out_of_order = self.lo.peek().unwrap().value > self.hi.peek().unwrap().0.value;
+            if !out_of_order {
+                break;
+            }
+
+            let lo_top = self.lo.pop().unwrap();
+            let hi_top = self.hi.pop().unwrap().0;
+            self.side_of.insert(lo_top.id, Side::Hi);
+            self.side_of.insert(hi_top.id, Side::Lo);
+            self.hi.push(Reverse(lo_top));
+            self.lo.push(hi_top);
+        }
+    }
+}
+
+#[cfg(test)]
+mod dual_heap_tests {
+    use super::*;
+
+    macro_rules! test_dual_heap_filter {
+        ($size:expr, $input:expr, $output:expr) => {
+            let mut filter = DualHeapFilter::new($size);
+            let output: Vec<_> = $input.iter().map(|&input| filter.consume(input)).collect();
+            assert_eq!(output, $output);
+        };
+    }
+
+    #[test]
+    fn single_peak_4() {
+        let input = vec![10, 20, 30, 100, 30, 20, 10];
+        let output = vec![10, 10, 20, 20, 30, 30, 20];
+
+        test_dual_heap_filter!(4, input, output);
+    }
+
+    #[test]
+    fn single_peak_5() {
+        let input = vec![10, 20, 30, 100, 30, 20, 10];
+        let output = vec![10, 10, 20, 20, 30, 30, 30];
+        test_dual_heap_filter!(5, input, output);
+    }
+
+    #[test]
+    fn triple_outlier_5() {
+        let input = vec![10, 10, 100, 100, 100, 10, 10];
+        let output = vec![10, 10, 10, 10, 100, 100, 100];
+        test_dual_heap_filter!(5, input, output);
+    }
+
+    #[test]
+    fn duplicate_values() {
+        let input = vec![5, 5, 5, 5, 5, 5, 5];
+        let output = vec![5, 5, 5, 5, 5, 5, 5];
+        test_dual_heap_filter!(5, input, output);
+    }
+
+    #[test]
+    fn median() {
+        let mut filter = DualHeapFilter::new(5);
+        for input in vec![70, 50, 30, 10, 20, 40, 60] {
+            filter.consume(input);
+        }
+        assert_eq!(filter.median(), 30);
+    }
+
+    #[test]
+    fn matches_linked_list_filter_after_expiry_empties_a_heap() {
+        // Regression test: expiring the sole `lo` occupant while `hi` still
+        // holds a value used to push the incoming value onto `lo`
+        // unconditionally (since `live_lo == 0`), leaving the two heaps out
+        // of order and producing the wrong median for window `[4, 5]`.
+        let input = vec![2, 4, 5];
+        let output = vec![2, 2, 4];
+        test_dual_heap_filter!(2, input, output);
+    }
+
+    #[test]
+    fn matches_linked_list_filter_randomized() {
+        // A small deterministic xorshift PRNG, so this differential test is
+        // reproducible without pulling in an external `rand` dependency.
+        struct XorShift(u64);
+
+        impl XorShift {
+            fn next_u64(&mut self) -> u64 {
+                self.0 ^= self.0 << 13;
+                self.0 ^= self.0 >> 7;
+                self.0 ^= self.0 << 17;
+                self.0
+            }
+
+            fn next_value(&mut self, range: u64) -> i64 {
+                (self.next_u64() % range) as i64
+            }
+        }
+
+        let mut rng = XorShift(0x243F_6A88_85A3_08D3);
+
+        for &size in &[1usize, 2, 3, 4, 5, 6, 7, 8] {
+            let mut dual = DualHeapFilter::new(size);
+            let mut linked = Filter::new(size);
+
+            for step in 0..500 {
+                // Keep the value range small to force plenty of duplicates:
+                let value = rng.next_value(10);
+                let expected = linked.consume(value);
+                let actual = dual.consume(value);
+                assert_eq!(
+                    actual, expected,
+                    "window size {size}, step {step}: DualHeapFilter diverged from Filter"
+                );
+            }
+        }
+    }
 }
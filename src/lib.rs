@@ -13,8 +13,6 @@
 #[cfg(all(not(feature = "std"), not(test)))]
 extern crate core as std;
 
-extern crate generic_array;
-
 #[cfg(feature = "std")]
 pub mod heap;
 
@@ -0,0 +1,418 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! An implementation of a stack-allocated, efficient O(n) median filter.
+
+use std::fmt;
+
+#[derive(Clone, PartialEq, Eq)]
+struct ListNode<T> {
+    value: Option<T>,
+    previous: usize,
+    next: usize,
+}
+
+impl<T> fmt::Debug for ListNode<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "@{:?}-{:?}-@{:?}", self.previous, self.value, self.next)
+    }
+}
+
+/// An implementation of a median filter with linear complexity, backed by a
+/// fixed-capacity buffer of size `N` living directly on the stack.
+///
+/// Identical in algorithm to [`heap::Filter`](crate::heap::Filter), but storing
+/// the embedded linked list in `[ListNode<T>; N]` rather than a heap-allocated
+/// `Vec`, so it requires no allocation. This is the primary filter for
+/// embedded/`no_std` users.
+#[derive(Clone, Debug)]
+pub struct Filter<T, const N: usize> {
+    // Buffer of list nodes:
+    buffer: [ListNode<T>; N],
+    // Cursor into circular buffer of data:
+    cursor: usize,
+    // Cursor to beginning of circular list:
+    head: usize,
+    // Cursor to median of circular list:
+    median: usize,
+}
+
+impl<T, const N: usize> Filter<T, N>
+where
+    T: Clone + PartialOrd,
+{
+    /// Creates a new median filter with a window size of `N`.
+    pub fn new() -> Self {
+        assert!(N > 0);
+
+        let buffer = core::array::from_fn(|i| ListNode {
+            value: None,
+            previous: (i + N - 1) % N,
+            next: (i + 1) % N,
+        });
+
+        Filter {
+            buffer,
+            cursor: 0,
+            head: 0,
+            median: 0,
+        }
+    }
+
+    /// Returns the window size of the filter.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns `true` if the filter has a length of `0`.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Returns the filter buffer's current median value, panicking if empty.
+    #[inline]
+    pub fn median(&self) -> T {
+        assert!(!self.buffer.is_empty());
+
+        unsafe { self.read_median() }
+    }
+
+    /// Returns the filter buffer's current min value, panicking if empty.
+    #[inline]
+    pub fn min(&self) -> T {
+        assert!(!self.buffer.is_empty());
+
+        unsafe { self.read_min() }
+    }
+
+    /// Returns the filter buffer's current max value, panicking if empty.
+    #[inline]
+    pub fn max(&self) -> T {
+        assert!(!self.buffer.is_empty());
+
+        unsafe { self.read_max() }
+    }
+
+    /// Applies the filter to a whole slice at once, returning the running median
+    /// for each element in turn.
+    ///
+    /// Requires the `std` feature, since it allocates a `Vec` to hold the output.
+    #[cfg(feature = "std")]
+    pub fn filter_slice(&mut self, input: &[T]) -> std::vec::Vec<T> {
+        input.iter().cloned().map(|value| self.consume(value)).collect()
+    }
+
+    /// Turns this filter into an iterator adapter that yields the running median
+    /// for each item consumed from `iter`, so callers don't have to hand-write the
+    /// `scan` loop around [`consume`](Self::consume) themselves.
+    ///
+    /// Unlike [`filter_slice`](Self::filter_slice), this works without `std`: it
+    /// reuses this filter's own buffer and performs no additional allocation.
+    pub fn medians<I>(self, iter: I) -> Medians<T, N, I>
+    where
+        I: Iterator<Item = T>,
+    {
+        Medians { filter: self, iter }
+    }
+
+    /// Applies a median filter to the consumed value.
+    ///
+    /// See [`heap::Filter::consume`](crate::heap::Filter::consume) for a full
+    /// description of the algorithm; this is the exact same linked-list walk,
+    /// operating on a stack-allocated buffer instead of a `Vec`.
+    pub fn consume(&mut self, value: T) -> T {
+        // If the current head is about to be overwritten
+        // we need to make sure to have the head point to
+        // the next node after the current head:
+        unsafe {
+            self.move_head_forward();
+        }
+
+        // Remove the node that is about to be overwritten
+        // from the linked list:
+        unsafe {
+            self.remove_node();
+        }
+
+        // Initialize `self.median` pointing
+        // to the first (smallest) node in the sorted list:
+        unsafe {
+            self.initialize_median();
+        }
+
+        // Search for the insertion index in the linked list
+        // in regards to `value` as the insertion index.
+        unsafe {
+            self.insert_value(&value);
+        }
+
+        // Update head to newly inserted node if
+        // cursor's value <= head's value or head is empty:
+        unsafe {
+            self.update_head(&value);
+        }
+
+        // If the filter has an even window size, then shift the median
+        // back one slot, so that it points to the left one
+        // of the middle pair of median values
+        unsafe {
+            self.adjust_median_for_even_length();
+        }
+
+        // Increment and wrap data in pointer:
+        unsafe {
+            self.increment_cursor();
+        }
+
+        // Read node value from buffer at `self.medium`:
+        unsafe { self.read_median() }
+    }
+
+    #[inline]
+    fn should_insert(&self, value: &T, current: usize, index: usize) -> bool {
+        if let Some(ref v) = self.buffer[current].value {
+            (index + 1 == self.len()) || (v >= value)
+        } else {
+            true
+        }
+    }
+
+    #[inline]
+    unsafe fn move_head_forward(&mut self) {
+        if self.cursor == self.head {
+            self.head = self.buffer[self.head].next;
+        }
+    }
+
+    #[inline]
+    unsafe fn remove_node(&mut self) {
+        let (predecessor, successor) = {
+            let node = &self.buffer[self.cursor];
+            (node.previous, node.next)
+        };
+        self.buffer[predecessor].next = successor;
+        self.buffer[self.cursor] = ListNode {
+            previous: usize::max_value(),
+            value: None,
+            next: usize::max_value(),
+        };
+        self.buffer[successor].previous = predecessor;
+    }
+
+    #[inline]
+    unsafe fn initialize_median(&mut self) {
+        self.median = self.head;
+    }
+
+    #[inline]
+    unsafe fn insert_value(&mut self, value: &T) {
+        let mut current = self.head;
+        let buffer_len = self.len();
+        let mut has_inserted = false;
+        for index in 0..buffer_len {
+            if !has_inserted {
+                let should_insert = self.should_insert(value, current, index);
+                if should_insert {
+                    // Insert previously removed node with new value
+                    // into linked list at given insertion index.
+                    self.insert(value, current);
+                    has_inserted = true;
+                }
+            }
+
+            // Shift median on every other element in the list,
+            // so that it ends up in the middle, eventually:
+            self.shift_median(index, current);
+
+            current = self.buffer[current].next;
+        }
+    }
+
+    #[inline]
+    unsafe fn insert(&mut self, value: &T, current: usize) {
+        let successor = current;
+        let predecessor = self.buffer[current].previous;
+        debug_assert!(self.buffer.len() == 1 || current != self.cursor);
+        self.buffer[predecessor].next = self.cursor;
+        self.buffer[self.cursor] = ListNode {
+            previous: predecessor,
+            value: Some(value.clone()),
+            next: successor,
+        };
+        self.buffer[successor].previous = self.cursor;
+    }
+
+    #[inline]
+    unsafe fn shift_median(&mut self, index: usize, current: usize) {
+        if (index & 0b1 == 0b1) && (self.buffer[current].value.is_some()) {
+            self.median = self.buffer[self.median].next;
+        }
+    }
+
+    #[inline]
+    unsafe fn update_head(&mut self, value: &T) {
+        let should_update_head = if let Some(ref head) = self.buffer[self.head].value {
+            value <= head
+        } else {
+            true
+        };
+
+        if should_update_head {
+            self.head = self.cursor;
+            self.median = self.buffer[self.median].previous;
+        }
+    }
+
+    #[inline]
+    unsafe fn adjust_median_for_even_length(&mut self) {
+        if self.len() % 2 == 0 {
+            self.median = self.buffer[self.median].previous;
+        }
+    }
+
+    #[inline]
+    unsafe fn increment_cursor(&mut self) {
+        self.cursor = (self.cursor + 1) % (self.len());
+    }
+
+    #[inline]
+    unsafe fn read_median(&self) -> T {
+        let index = self.median;
+        self.buffer[index].value.clone().unwrap()
+    }
+
+    #[inline]
+    unsafe fn read_min(&self) -> T {
+        let index = self.head;
+        self.buffer[index].value.clone().unwrap()
+    }
+
+    #[inline]
+    unsafe fn read_max(&self) -> T {
+        let index = (self.cursor + self.len() - 1) % (self.len());
+        self.buffer[index].value.clone().unwrap()
+    }
+}
+
+/// An iterator adapter yielding the running median of each item consumed from
+/// an inner iterator.
+///
+/// Created by [`Filter::medians`].
+pub struct Medians<T, const N: usize, I> {
+    filter: Filter<T, N>,
+    iter: I,
+}
+
+impl<T, const N: usize, I> Iterator for Medians<T, N, I>
+where
+    T: Clone + PartialOrd,
+    I: Iterator<Item = T>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let value = self.iter.next()?;
+        Some(self.filter.consume(value))
+    }
+}
+
+impl<T, const N: usize> Default for Filter<T, N>
+where
+    T: Clone + PartialOrd,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! test_filter {
+        ($size:expr, $input:expr, $output:expr) => {
+            let filter = Filter::<_, $size>::new();
+            let output: Vec<_> = $input
+                .iter()
+                .scan(filter, |filter, &input| Some(filter.consume(input)))
+                .collect();
+            assert_eq!(output, $output);
+        };
+    }
+
+    #[test]
+    fn single_peak_4() {
+        let input = vec![10, 20, 30, 100, 30, 20, 10];
+        let output = vec![10, 10, 20, 20, 30, 30, 20];
+
+        test_filter!(4, input, output);
+    }
+
+    #[test]
+    fn single_peak_5() {
+        let input = vec![10, 20, 30, 100, 30, 20, 10];
+        let output = vec![10, 10, 20, 20, 30, 30, 30];
+        test_filter!(5, input, output);
+    }
+
+    #[test]
+    fn single_valley_4() {
+        let input = vec![90, 80, 70, 10, 70, 80, 90];
+        let output = vec![90, 80, 80, 70, 70, 70, 70];
+        test_filter!(4, input, output);
+    }
+
+    #[test]
+    fn triple_outlier_5() {
+        let input = vec![10, 10, 100, 100, 100, 10, 10];
+        let output = vec![10, 10, 10, 10, 100, 100, 100];
+        test_filter!(5, input, output);
+    }
+
+    #[test]
+    fn ascending_4() {
+        let input = vec![10, 20, 30, 40, 50, 60, 70];
+        let output = vec![10, 10, 20, 20, 30, 40, 50];
+        test_filter!(4, input, output);
+    }
+
+    #[test]
+    fn min_max_median() {
+        let mut filter = Filter::<_, 5>::new();
+        for input in vec![70, 50, 30, 10, 20, 40, 60] {
+            filter.consume(input);
+        }
+        assert_eq!(filter.min(), 10);
+        assert_eq!(filter.max(), 60);
+        assert_eq!(filter.median(), 30);
+    }
+
+    #[test]
+    fn is_empty_returns_bool() {
+        let filter = Filter::<i32, 5>::new();
+        let is_empty: bool = filter.is_empty();
+        assert!(!is_empty);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn filter_slice() {
+        let mut filter = Filter::<_, 5>::new();
+        let input = vec![70, 50, 30, 10, 20, 40, 60];
+        let output = filter.filter_slice(&input);
+        assert_eq!(output, vec![70, 50, 50, 30, 30, 30, 30]);
+    }
+
+    #[test]
+    fn medians() {
+        let input = vec![70, 50, 30, 10, 20, 40, 60];
+        let output: Vec<_> = Filter::<_, 5>::new().medians(input.into_iter()).collect();
+        assert_eq!(output, vec![70, 50, 50, 30, 30, 30, 30]);
+    }
+}